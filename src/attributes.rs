@@ -0,0 +1,93 @@
+//! Tool-attribute support for `#[klint::...]`, replacing the old stringly-typed
+//! `def_path_str`/substring matching with proper `DefId` attribute lookups.
+use rustc_ast::ast;
+use rustc_hir::def_id::DefId;
+use rustc_hir::CRATE_HIR_ID;
+use rustc_lint::LateContext;
+
+/// Marks an allocation (or other) entry point as fallible outright, seeding the `visited`/
+/// pruned set the same way the old hard-coded `"alloc::alloc::__rust_alloc"`-style allowlist
+/// did, but attachable to any item instead of requiring an exact crate-provided path.
+pub const FALLIBLE: &str = "fallible";
+
+/// Placed on a marker function; anything it calls directly is assumed fallible and pruned from
+/// the infallible-allocation propagation, exactly like the old `assume_fallible`
+/// substring-matched helper.
+pub const ASSUME_FALLIBLE: &str = "assume_fallible";
+
+/// Placed on a marker function; anything it calls directly is assumed to not reach a panic
+/// handler and is pruned from the panic-reachability propagation.
+pub const ASSUME_PANIC_FREE: &str = "assume_panic_free";
+
+/// Crate-root attribute: every item defined in a crate carrying `#![klint::infallible_allocator]`
+/// that has no walked body of its own (no entry in the forward graph) is treated as an
+/// infallible-allocator root, generalizing the old hard-coded check for the
+/// `alloc` crate's origin so that a downstream `no_std`/kernel crate can provide its own
+/// allocator shims.
+pub const INFALLIBLE_ALLOCATOR: &str = "infallible_allocator";
+
+/// Does `def_id` carry the tool attribute `#[klint::<name>]`?
+pub fn has_attr(cx: &LateContext<'_>, def_id: DefId, name: &str) -> bool {
+    cx.tcx.get_attrs(def_id).iter().any(|attr| is_klint_attr(attr, name))
+}
+
+/// Does the crate that `def_id` lives in carry the crate-root attribute `#![klint::<name>]`?
+pub fn krate_has_attr(cx: &LateContext<'_>, def_id: DefId, name: &str) -> bool {
+    if def_id.is_local() {
+        cx.tcx
+            .hir()
+            .attrs(CRATE_HIR_ID)
+            .iter()
+            .any(|attr| is_klint_attr(attr, name))
+    } else {
+        cx.tcx
+            .get_attrs(def_id.krate.as_def_id())
+            .iter()
+            .any(|attr| is_klint_attr(attr, name))
+    }
+}
+
+/// Is `attr` the tool attribute `#[klint::<name>]`? Plain `///`/`//!` doc comments are
+/// `AttrKind::DocComment`, not `AttrKind::Normal`, so `get_normal_item()` panics on them — every
+/// crate has doc comments, so this guard is load-bearing, not defensive-for-show.
+fn is_klint_attr(attr: &ast::Attribute, name: &str) -> bool {
+    if attr.is_doc_comment() {
+        return false;
+    }
+    let segments = &attr.get_normal_item().path.segments;
+    segments.len() == 2
+        && segments[0].ident.as_str() == "klint"
+        && segments[1].ident.as_str() == name
+}
+
+/// Read a crate-root attribute of the form `#![klint::<name> = "value"]`, local to this crate
+/// only (there is no sensible notion of reading a string-valued attribute off a foreign crate
+/// root here).
+pub fn crate_str_attr(cx: &LateContext<'_>, name: &str) -> Option<String> {
+    cx.tcx
+        .hir()
+        .attrs(CRATE_HIR_ID)
+        .iter()
+        .find(|attr| is_klint_attr(attr, name))
+        .and_then(|attr| attr.value_str())
+        .map(|s| s.to_string())
+}
+
+/// Extra `def_path_str`-formatted paths to treat as fallible-allocation roots, supplied via a
+/// crate-root `#![klint::fallible_paths("path::to::fn", ...)]` attribute. This is the escape
+/// hatch for entries that can't be attributed directly because they live in a crate klint
+/// doesn't control (e.g. `alloc`).
+pub fn extra_fallible_paths(cx: &LateContext<'_>) -> Vec<String> {
+    cx.tcx
+        .hir()
+        .attrs(CRATE_HIR_ID)
+        .iter()
+        .filter(|attr| is_klint_attr(attr, "fallible_paths"))
+        .flat_map(|attr| {
+            attr.meta_item_list()
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|nested| nested.lit().and_then(|lit| lit.str_lit()).map(|s| s.to_string()))
+        })
+        .collect()
+}
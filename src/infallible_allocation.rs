@@ -1,102 +1,72 @@
-use rustc_data_structures::fx::{FxHashMap, FxHashSet};
+use rustc_data_structures::fx::FxHashSet;
 use rustc_hir as hir;
 use rustc_lint::{LateContext, LateLintPass, LintContext};
-use rustc_middle::mir::mono::MonoItem;
-use rustc_middle::ty::Instance;
-use rustc_mir::monomorphize::collector::MonoItemCollectionMode;
 use rustc_session::{declare_lint_pass, declare_tool_lint};
-use rustc_span::source_map::Spanned;
 use rustc_span::symbol::sym;
 
+use crate::attributes;
+use crate::mono_graph;
+use crate::report;
+
 declare_tool_lint! {
     pub klint::INFALLIBLE_ALLOCATION,
     Warn,
     ""
 }
 
+/// Fallible allocation entry points klint knows about out of the box. Crates that can't
+/// attribute these directly (because they live in `alloc`) don't need to; anything else can be
+/// marked with `#[klint::fallible]` or extend this list with a crate-root
+/// `#![klint::fallible_paths("path::to::fn")]` attribute instead of patching klint.
+const DEFAULT_FALLIBLE_PATHS: &[&str] = &[
+    // These are fallible allocation functions that return null ptr on failure.
+    "alloc::alloc::__rust_alloc",
+    "alloc::alloc::__rust_alloc_zeroed",
+    "alloc::alloc::__rust_realloc",
+    "alloc::alloc::__rust_dealloc",
+    // Fallible allocation function
+    "alloc::string::String::try_reserve",
+    "alloc::string::String::try_reserve_exact",
+];
+
 declare_lint_pass!(InfallibleAllocation => [INFALLIBLE_ALLOCATION]);
 
 impl<'tcx> LateLintPass<'tcx> for InfallibleAllocation {
     fn check_crate(&mut self, cx: &LateContext<'tcx>, _: &'tcx hir::Crate<'tcx>) {
-        // Collect all mono items to be codegened with this crate. Discard the inline map, it does
-        // not contain enough information for us; we will collect them ourselves later.
-        //
-        // Use eager mode here so dead code is also linted on.
-        let access_map = super::monomorphize_collector::collect_crate_mono_items(
-            cx.tcx,
-            MonoItemCollectionMode::Eager,
-        )
-        .1;
-
-        // Build a forward and backward dependency graph with span information.
-        let mut forward = FxHashMap::default();
-        let mut backward = FxHashMap::<_, Vec<_>>::default();
-
-        access_map.iter_accesses(|accessor, accessees| {
-            let accessor = match accessor {
-                MonoItem::Static(s) => Instance::mono(cx.tcx, s),
-                MonoItem::Fn(v) => v,
-                _ => return,
-            };
-
-            let fwd_list = forward
-                .entry(accessor)
-                .or_insert_with(|| Vec::with_capacity(accessees.len()));
-            let mut def_span = None;
-
-            for accessee in accessees {
-                let accessee_node = match accessee.node {
-                    MonoItem::Static(s) => Instance::mono(cx.tcx, s),
-                    MonoItem::Fn(v) => v,
-                    _ => return,
-                };
-
-                // For const-evaluated items, they're collected from miri, which does not have span
-                // information. Synthesize one with the accessor.
-                let span = if accessee.span.is_dummy() {
-                    *def_span.get_or_insert_with(|| cx.tcx.def_span(accessor.def_id()))
-                } else {
-                    accessee.span
-                };
-
-                fwd_list.push(Spanned {
-                    node: accessee_node,
-                    span,
-                });
-                backward.entry(accessee_node).or_default().push(Spanned {
-                    node: accessor,
-                    span,
-                });
-            }
-        });
+        // Build a forward and backward dependency graph with span information. This is shared
+        // with the other mono-item-reachability lints (e.g. `panic_reachability`) so they all
+        // agree on the same set of edges.
+        let mono_graph::MonoGraph {
+            forward,
+            backward,
+            virtual_sinks,
+        } = mono_graph::build(cx);
 
         // Find all fallible functions
         let mut visited = FxHashSet::default();
+        let extra_fallible_paths = attributes::extra_fallible_paths(cx);
 
         for accessee in backward.keys() {
-            let name = cx.tcx.def_path_str(accessee.def_id());
-
-            // Anything (directly) called by assume_fallible is considered to be fallible.
-            if name.contains("assume_fallible") {
+            // Anything (directly) called by a `#[klint::assume_fallible]` marker is considered
+            // to be fallible.
+            if attributes::has_attr(cx, accessee.def_id(), attributes::ASSUME_FALLIBLE) {
                 visited.insert(*accessee);
-                for accessor in forward.get(&accessee).unwrap_or(&Vec::new()) {
+                for accessor in forward.get(accessee).unwrap_or(&Vec::new()) {
                     visited.insert(accessor.node);
                 }
                 continue;
             }
 
-            match name.as_str() {
-                // These are fallible allocation functions that return null ptr on failure.
-                "alloc::alloc::__rust_alloc"
-                | "alloc::alloc::__rust_alloc_zeroed"
-                | "alloc::alloc::__rust_realloc"
-                | "alloc::alloc::__rust_dealloc"
-                // Fallible allocation function
-                | "alloc::string::String::try_reserve"
-                | "alloc::string::String::try_reserve_exact" => {
-                    visited.insert(*accessee);
-                }
-                _ => (),
+            if attributes::has_attr(cx, accessee.def_id(), attributes::FALLIBLE) {
+                visited.insert(*accessee);
+                continue;
+            }
+
+            let name = cx.tcx.def_path_str(accessee.def_id());
+            if DEFAULT_FALLIBLE_PATHS.contains(&name.as_str())
+                || extra_fallible_paths.iter().any(|path| path == &name)
+            {
+                visited.insert(*accessee);
             }
         }
 
@@ -110,13 +80,23 @@ impl<'tcx> LateLintPass<'tcx> for InfallibleAllocation {
                 continue;
             }
 
-            if cx.tcx.original_crate_name(accessee.def_id().krate) == sym::alloc {
-                // If this item originates from alloc crate, mark it as infallible.
-                // Add item to the allowlist above if there are false positives.
+            // `alloc` is an infallible-allocator root out of the box; downstream `no_std`/
+            // kernel crates providing their own allocator shims can mark their crate root with
+            // `#![klint::infallible_allocator]` instead of klint hard-coding their crate name.
+            if cx.tcx.original_crate_name(accessee.def_id().krate) == sym::alloc
+                || attributes::krate_has_attr(cx, accessee.def_id(), attributes::INFALLIBLE_ALLOCATOR)
+            {
                 work_queue.push(*accessee);
             }
         }
 
+        // Virtual-dispatch sinks are synthesized for `dyn Trait` call sites whose concrete
+        // implementations couldn't be resolved; treat them as unconditionally infallible so
+        // calls through `dyn Trait` are flagged rather than silently missed. A sink for a local
+        // trait's method is itself a local item, so propagation stops at it and it is not
+        // reported: only sinks for non-local traits lead to a warning here.
+        work_queue.extend(virtual_sinks.iter().copied());
+
         // Propagate infallible property.
         while let Some(work_item) = work_queue.pop() {
             if visited.contains(&work_item) {
@@ -136,6 +116,11 @@ impl<'tcx> LateLintPass<'tcx> for InfallibleAllocation {
             }
         }
 
+        // If opted into, accumulate every flagged site as a JSON-serializable finding, so CI
+        // can diff the set of infallible-allocation sites across commits.
+        let report_path = report::output_path(cx, "infallible_allocation");
+        let mut findings = Vec::new();
+
         for (accessor, accessees) in forward.iter() {
             // Don't report on non-local items
             if !accessor.def_id().is_local() {
@@ -173,72 +158,48 @@ impl<'tcx> LateLintPass<'tcx> for InfallibleAllocation {
                         ));
 
                         // For generic functions try to display a stacktrace until a non-generic one.
-                        let mut caller = *accessor;
                         let mut visited = FxHashSet::default();
                         visited.insert(*accessor);
                         visited.insert(accessee);
-                        while caller.substs.non_erasable_generics().next().is_some() {
-                            let spanned_caller = match backward
-                                .get(&caller)
-                                .map(|x| &**x)
-                                .unwrap_or(&[])
-                                .iter()
-                                .find(|x| !visited.contains(&x.node))
-                            {
-                                Some(v) => *v,
-                                None => break,
-                            };
-                            caller = spanned_caller.node;
-                            visited.insert(caller);
-
-                            diag.span_note(
-                                spanned_caller.span,
-                                &format!(
-                                    "which is called from `{}`",
-                                    cx.tcx
-                                        .def_path_str_with_substs(caller.def_id(), caller.substs)
-                                ),
-                            );
-                        }
+                        let caller_chain = mono_graph::note_generic_callers(
+                            cx, &mut diag, &backward, &mut visited, *accessor,
+                        );
 
                         // Generate some help messages for why the function is determined to be infallible.
-                        let mut msg: &str = &format!(
-                            "`{}` is determined to be infallible because it",
-                            accessee_path
+                        let callee_chain = mono_graph::note_property_chain(
+                            cx,
+                            &mut diag,
+                            &forward,
+                            &infallible,
+                            &mut visited,
+                            accessee,
+                            &accessee_path,
+                            "may call alloc_error_handler",
+                            "is determined to be infallible",
                         );
-                        let mut callee = accessee;
-                        loop {
-                            let callee_callee = match forward
-                                .get(&callee)
-                                .map(|x| &**x)
-                                .unwrap_or(&[])
-                                .iter()
-                                .find(|x| {
-                                    infallible.contains(&x.node) && !visited.contains(&x.node)
-                                }) {
-                                Some(v) => v,
-                                None => break,
-                            };
-                            callee = callee_callee.node;
-                            visited.insert(callee);
-
-                            diag.span_note(
-                                callee_callee.span,
-                                &format!(
-                                    "{} calls into `{}`",
-                                    msg,
-                                    cx.tcx
-                                        .def_path_str_with_substs(callee.def_id(), callee.substs)
-                                ),
-                            );
-                            msg = "which";
+
+                        if report_path.is_some() {
+                            let mut chain: Vec<_> =
+                                caller_chain.iter().map(|hop| report::chain_hop(cx, hop)).collect();
+                            chain.extend(callee_chain.iter().map(|hop| report::chain_hop(cx, hop)));
+                            findings.push(report::Finding {
+                                accessor: cx
+                                    .tcx
+                                    .def_path_str_with_substs(accessor.def_id(), accessor.substs),
+                                accessor_span: report::loc(cx, item.span),
+                                accessee: accessee_path.clone(),
+                                chain,
+                            });
                         }
 
-                        diag.note(&format!("{} may call alloc_error_handler", msg));
                         diag.emit();
                     });
                 }
             }
         }
+
+        if let Some(path) = &report_path {
+            report::write(path, &findings);
+        }
     }
 }
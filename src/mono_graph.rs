@@ -0,0 +1,311 @@
+use rustc_data_structures::fx::{FxHashMap, FxHashSet};
+use rustc_errors::DiagnosticBuilder;
+use rustc_lint::LateContext;
+use rustc_middle::mir::{CastKind, Rvalue, StatementKind};
+use rustc_middle::ty::adjustment::PointerCast;
+use rustc_middle::ty::{self, Instance, InstanceDef, Ty, TyKind};
+use rustc_span::source_map::Spanned;
+use rustc_span::Span;
+
+use crate::reachability;
+
+/// The forward and backward mono-item dependency graphs, shared by every lint that needs to
+/// reason about "what can this function's code end up calling".
+///
+/// `forward[f]` lists everything `f` may call (with the call-site span); `backward[f]` lists
+/// everything that may call `f`. Both are built once per crate and handed to each consuming
+/// lint, so that e.g. [`crate::infallible_allocation`] and [`crate::panic_reachability`] agree
+/// on exactly the same set of edges.
+pub struct MonoGraph<'tcx> {
+    pub forward: FxHashMap<Instance<'tcx>, Vec<Spanned<Instance<'tcx>>>>,
+    pub backward: FxHashMap<Instance<'tcx>, Vec<Spanned<Instance<'tcx>>>>,
+    /// Synthesized virtual-dispatch sinks: nodes standing in for a `dyn Trait` method call
+    /// whose concrete implementation set could not be pinned down. Every lint built on this
+    /// graph should treat these as unconditionally "flagged" (infallible / panic-reaching),
+    /// since we have no better information about what they do.
+    pub virtual_sinks: FxHashSet<Instance<'tcx>>,
+}
+
+/// Build the forward/backward dependency graph for every mono item reachable from this crate.
+pub fn build<'tcx>(cx: &LateContext<'tcx>) -> MonoGraph<'tcx> {
+    // Walk the MIR of every reachable item ourselves rather than going through rustc's private
+    // monomorphization collector; see `crate::reachability` for why.
+    let reachability::Reachability { mut forward, mut backward } = reachability::walk(cx);
+
+    let virtual_sinks = add_virtual_dispatch_edges(cx, &mut forward, &mut backward);
+
+    MonoGraph {
+        forward,
+        backward,
+        virtual_sinks,
+    }
+}
+
+/// A single `Rvalue::Cast` unsizing coercion to `dyn Trait`, found while walking an instance's
+/// MIR.
+struct Coercion<'tcx> {
+    /// The instance whose MIR contains the coercion (the edge's `from` node).
+    site: Instance<'tcx>,
+    /// The existential trait predicates of the `dyn Trait` being coerced to (i.e. the trait and
+    /// its own generic args, but not `Self` — that's supplied separately per impl).
+    principal: ty::PolyExistentialTraitRef<'tcx>,
+    span: Span,
+}
+
+/// The walk in [`crate::reachability`] does not follow `Rvalue::Cast` unsizing coercions, so it
+/// emits no access-graph edges through vtables: a `fn foo<T: Trait>(x: T) { x.panicky() }` /
+/// `dyn Trait` call site simply has no forward edge to any of `Trait`'s implementations. Patch
+/// the graph built above by scanning every collected instance's MIR for unsizing coercions to
+/// `dyn Trait`, and wiring up a conservative edge to everything that could end up in that
+/// vtable.
+fn add_virtual_dispatch_edges<'tcx>(
+    cx: &LateContext<'tcx>,
+    forward: &mut FxHashMap<Instance<'tcx>, Vec<Spanned<Instance<'tcx>>>>,
+    backward: &mut FxHashMap<Instance<'tcx>, Vec<Spanned<Instance<'tcx>>>>,
+) -> FxHashSet<Instance<'tcx>> {
+    let tcx = cx.tcx;
+    let param_env = ty::ParamEnv::reveal_all();
+    let mut virtual_sinks = FxHashSet::default();
+
+    // Pass 1: find every coercion site, and for each trait, every concrete `Self` type that is
+    // actually observed being unsized to it somewhere in this crate graph. We deliberately do
+    // NOT consult rustc's global impl table here (`TyCtxt::for_each_impl`) — that returns every
+    // impl of the trait visible anywhere in the dependency graph, most of which are never
+    // unsized to `dyn Trait` at all, which would make a single `dyn Display`/`dyn Drop`-style
+    // cast site noisily fan out to unrelated impls and be a perf cliff for common traits.
+    let mut self_tys_of_trait: FxHashMap<rustc_hir::def_id::DefId, FxHashSet<Ty<'tcx>>> =
+        FxHashMap::default();
+    let mut coercions: Vec<Coercion<'tcx>> = Vec::new();
+
+    let instances: Vec<_> = forward.keys().copied().collect();
+    for instance in instances {
+        if !matches!(instance.def, InstanceDef::Item(_)) || !tcx.is_mir_available(instance.def_id())
+        {
+            continue;
+        }
+
+        let body = tcx.optimized_mir(instance.def_id());
+        for block in body.basic_blocks() {
+            for stmt in &block.statements {
+                let (rvalue, span) = match &stmt.kind {
+                    StatementKind::Assign(box (_, rvalue)) => (rvalue, stmt.source_info.span),
+                    _ => continue,
+                };
+                let (source_op, target_ty) = match rvalue {
+                    Rvalue::Cast(CastKind::Pointer(PointerCast::Unsize), source, target_ty) => {
+                        (source, *target_ty)
+                    }
+                    _ => continue,
+                };
+                let target_ty =
+                    instance.subst_mir_and_normalize_erasing_regions(tcx, param_env, target_ty);
+                let principal = match dyn_trait_principal(target_ty) {
+                    Some(principal) => principal,
+                    None => continue,
+                };
+
+                let source_ty = source_op.ty(&body.local_decls, tcx);
+                let source_ty =
+                    instance.subst_mir_and_normalize_erasing_regions(tcx, param_env, source_ty);
+                self_tys_of_trait
+                    .entry(principal.def_id())
+                    .or_default()
+                    .insert(peel_pointer(source_ty));
+
+                coercions.push(Coercion {
+                    site: instance,
+                    principal,
+                    span,
+                });
+            }
+        }
+    }
+
+    // Pass 2: wire up edges now that `self_tys_of_trait` has observed every coercion in the
+    // crate graph, using each site's own trait-ref args (so `dyn SomeTrait<u32>` resolves
+    // against `u32`, not a generic placeholder).
+    for coercion in coercions {
+        let trait_def_id = coercion.principal.def_id();
+        let methods: Vec<_> = tcx
+            .associated_items(trait_def_id)
+            .in_definition_order()
+            .filter(|item| item.kind == ty::AssocKind::Fn)
+            .map(|item| item.def_id)
+            .collect();
+
+        let self_tys = self_tys_of_trait.get(&trait_def_id);
+        let mut resolved_any = false;
+        for &self_ty in self_tys.into_iter().flatten() {
+            let trait_ref = coercion.principal.with_self_ty(tcx, self_ty).skip_binder();
+            for &method_def_id in &methods {
+                let substs = ty::InternalSubsts::for_item(tcx, method_def_id, |param, _| {
+                    match trait_ref.substs.get(param.index as usize) {
+                        Some(arg) => *arg,
+                        None => tcx.mk_param_from_def(param),
+                    }
+                });
+                if let Ok(Some(resolved)) = Instance::resolve(tcx, param_env, method_def_id, substs) {
+                    resolved_any = true;
+                    link(forward, backward, coercion.site, resolved, coercion.span);
+                }
+            }
+        }
+
+        if !resolved_any {
+            for &method_def_id in &methods {
+                let sink = Instance {
+                    def: InstanceDef::Virtual(method_def_id, 0),
+                    substs: ty::InternalSubsts::identity_for_item(tcx, method_def_id),
+                };
+                virtual_sinks.insert(sink);
+                link(forward, backward, coercion.site, sink, coercion.span);
+            }
+        }
+    }
+
+    virtual_sinks
+}
+
+fn link<'tcx>(
+    forward: &mut FxHashMap<Instance<'tcx>, Vec<Spanned<Instance<'tcx>>>>,
+    backward: &mut FxHashMap<Instance<'tcx>, Vec<Spanned<Instance<'tcx>>>>,
+    from: Instance<'tcx>,
+    to: Instance<'tcx>,
+    span: Span,
+) {
+    forward.entry(from).or_default().push(Spanned { node: to, span });
+    backward.entry(to).or_default().push(Spanned { node: from, span });
+}
+
+/// Strip a reference/raw pointer/`Box` layer off `ty`, if present.
+fn peel_pointer(ty: Ty<'_>) -> Ty<'_> {
+    match ty.kind() {
+        TyKind::Ref(_, inner, _) => *inner,
+        TyKind::RawPtr(mt) => mt.ty,
+        TyKind::Adt(adt, substs) if adt.is_box() => substs.type_at(0),
+        _ => ty,
+    }
+}
+
+/// If `ty` is (possibly behind a reference/`Box`) a `dyn Trait`, return its principal trait ref
+/// — the trait `DefId` together with the trait's own generic args (but not `Self`, which an
+/// existential trait ref doesn't carry).
+fn dyn_trait_principal(ty: Ty<'_>) -> Option<ty::PolyExistentialTraitRef<'_>> {
+    match peel_pointer(ty).kind() {
+        TyKind::Dynamic(preds, _) => preds.principal(),
+        _ => None,
+    }
+}
+
+/// Walk backward from `start` through callers that are still generic, collecting each hop, so
+/// callers can show the chain up to the first caller that is fully monomorphized (or until no
+/// caller outside `visited` is left, whichever comes first).
+pub fn generic_caller_chain<'tcx>(
+    backward: &FxHashMap<Instance<'tcx>, Vec<Spanned<Instance<'tcx>>>>,
+    visited: &mut FxHashSet<Instance<'tcx>>,
+    start: Instance<'tcx>,
+) -> Vec<Spanned<Instance<'tcx>>> {
+    let mut chain = Vec::new();
+    let mut caller = start;
+    while caller.substs.non_erasable_generics().next().is_some() {
+        let spanned_caller = match backward
+            .get(&caller)
+            .map(|x| &**x)
+            .unwrap_or(&[])
+            .iter()
+            .find(|x| !visited.contains(&x.node))
+        {
+            Some(v) => *v,
+            None => break,
+        };
+        caller = spanned_caller.node;
+        visited.insert(caller);
+        chain.push(spanned_caller);
+    }
+    chain
+}
+
+/// Walk forward from `start` through callees that are also in `flagged`, collecting each hop,
+/// to explain *why* `start` ended up with the flagged property (e.g. infallibility, or being
+/// panic-reachable).
+pub fn property_chain<'tcx>(
+    forward: &FxHashMap<Instance<'tcx>, Vec<Spanned<Instance<'tcx>>>>,
+    flagged: &FxHashSet<Instance<'tcx>>,
+    visited: &mut FxHashSet<Instance<'tcx>>,
+    start: Instance<'tcx>,
+) -> Vec<Spanned<Instance<'tcx>>> {
+    let mut chain = Vec::new();
+    let mut callee = start;
+    loop {
+        let callee_callee = match forward
+            .get(&callee)
+            .map(|x| &**x)
+            .unwrap_or(&[])
+            .iter()
+            .find(|x| flagged.contains(&x.node) && !visited.contains(&x.node))
+        {
+            Some(v) => v,
+            None => break,
+        };
+        callee = callee_callee.node;
+        visited.insert(callee);
+        chain.push(*callee_callee);
+    }
+    chain
+}
+
+/// Span-note the chain returned by [`generic_caller_chain`] onto a diagnostic.
+///
+/// Shared between every lint built on top of [`MonoGraph`] so the "stacktrace" they print is
+/// identical in shape.
+pub fn note_generic_callers<'tcx>(
+    cx: &LateContext<'tcx>,
+    diag: &mut DiagnosticBuilder<'_>,
+    backward: &FxHashMap<Instance<'tcx>, Vec<Spanned<Instance<'tcx>>>>,
+    visited: &mut FxHashSet<Instance<'tcx>>,
+    start: Instance<'tcx>,
+) -> Vec<Spanned<Instance<'tcx>>> {
+    let chain = generic_caller_chain(backward, visited, start);
+    for hop in &chain {
+        diag.span_note(
+            hop.span,
+            &format!(
+                "which is called from `{}`",
+                cx.tcx.def_path_str_with_substs(hop.node.def_id(), hop.node.substs)
+            ),
+        );
+    }
+    chain
+}
+
+/// Span-note the chain returned by [`property_chain`] onto a diagnostic, plus a final note at
+/// the leaf explaining the ultimate cause (e.g. "may call alloc_error_handler").
+pub fn note_property_chain<'tcx>(
+    cx: &LateContext<'tcx>,
+    diag: &mut DiagnosticBuilder<'_>,
+    forward: &FxHashMap<Instance<'tcx>, Vec<Spanned<Instance<'tcx>>>>,
+    flagged: &FxHashSet<Instance<'tcx>>,
+    visited: &mut FxHashSet<Instance<'tcx>>,
+    start: Instance<'tcx>,
+    start_path: &str,
+    leaf_msg: &str,
+    reason_verb: &str,
+) -> Vec<Spanned<Instance<'tcx>>> {
+    let chain = property_chain(forward, flagged, visited, start);
+
+    let mut msg = format!("`{}` {} because it", start_path, reason_verb);
+    for hop in &chain {
+        diag.span_note(
+            hop.span,
+            &format!(
+                "{} calls into `{}`",
+                msg,
+                cx.tcx.def_path_str_with_substs(hop.node.def_id(), hop.node.substs)
+            ),
+        );
+        msg = "which".to_string();
+    }
+    diag.note(&format!("{} {}", msg, leaf_msg));
+
+    chain
+}
@@ -0,0 +1,193 @@
+use rustc_data_structures::fx::FxHashSet;
+use rustc_hir as hir;
+use rustc_lint::{LateContext, LateLintPass, LintContext};
+use rustc_session::{declare_lint_pass, declare_tool_lint};
+
+use crate::attributes;
+use crate::mono_graph;
+use crate::report;
+
+declare_tool_lint! {
+    pub klint::PANIC,
+    Warn,
+    ""
+}
+
+declare_lint_pass!(PanicReachability => [PANIC]);
+
+/// Leaf functions that, once reached, unconditionally invoke the panic handler. `core`'s
+/// `Index`/`IndexMut` shims are included here too: there is currently no way to tell apart a
+/// bounds-checked index from one the optimizer proved in range, so we conservatively treat all
+/// indexing as potentially panicking.
+const PANIC_LEAVES: &[&str] = &[
+    "core::panicking::panic",
+    "core::panicking::panic_fmt",
+    "core::panicking::panic_nounwind",
+    "core::panicking::panic_bounds_check",
+    "core::result::unwrap_failed",
+    "core::option::expect_failed",
+    "core::ops::Index::index",
+    "core::ops::IndexMut::index_mut",
+];
+
+impl<'tcx> LateLintPass<'tcx> for PanicReachability {
+    fn check_crate(&mut self, cx: &LateContext<'tcx>, _: &'tcx hir::Crate<'tcx>) {
+        // Reuse the same forward/backward dependency graph the infallible-allocation lint
+        // builds, so the two lints never disagree about what calls what.
+        let mono_graph::MonoGraph {
+            forward,
+            backward,
+            virtual_sinks,
+        } = mono_graph::build(cx);
+
+        // Seed the worklist with the known panic leaves, minus anything explicitly marked
+        // panic-free by the user.
+        let mut visited = FxHashSet::default();
+        let mut work_queue = Vec::new();
+        let index_traits = [
+            cx.tcx.lang_items().index_trait(),
+            cx.tcx.lang_items().index_mut_trait(),
+        ];
+
+        for accessee in backward.keys() {
+            // Anything (directly) called by a `#[klint::assume_panic_free]` marker is
+            // considered to be panic-free.
+            if attributes::has_attr(cx, accessee.def_id(), attributes::ASSUME_PANIC_FREE) {
+                visited.insert(*accessee);
+                for accessor in forward.get(accessee).unwrap_or(&Vec::new()) {
+                    visited.insert(accessor.node);
+                }
+                continue;
+            }
+
+            // Methods of concrete `Index`/`IndexMut` impls don't print as the trait paths listed
+            // in `PANIC_LEAVES`, so match them by the trait they implement.
+            let implements_index = cx
+                .tcx
+                .impl_of_method(accessee.def_id())
+                .and_then(|impl_id| cx.tcx.trait_id_of_impl(impl_id))
+                .map_or(false, |trait_id| index_traits.contains(&Some(trait_id)));
+
+            let name = cx.tcx.def_path_str(accessee.def_id());
+            if PANIC_LEAVES.contains(&name.as_str()) || implements_index {
+                work_queue.push(*accessee);
+            }
+        }
+
+        // Virtual-dispatch sinks are synthesized for `dyn Trait` call sites whose concrete
+        // implementations couldn't be resolved; treat them as unconditionally panic-reaching so
+        // calls through `dyn Trait` are flagged rather than silently missed.
+        work_queue.extend(virtual_sinks.iter().copied());
+
+        // Propagate "can panic" backward through the call graph, same as the `infallible`
+        // set is propagated in `infallible_allocation`.
+        let mut panicking = FxHashSet::default();
+        while let Some(work_item) = work_queue.pop() {
+            if visited.contains(&work_item) {
+                continue;
+            }
+
+            panicking.insert(work_item);
+            visited.insert(work_item);
+
+            // Stop at local items to prevent over-linting; we only want to report on the
+            // outermost local caller. A virtual-dispatch sink for a local trait's method is
+            // local too, but its callers still have to be reached.
+            if work_item.def_id().is_local() && !virtual_sinks.contains(&work_item) {
+                continue;
+            }
+
+            for accessor in backward.get(&work_item).unwrap_or(&Vec::new()) {
+                work_queue.push(accessor.node);
+            }
+        }
+
+        // If opted into, accumulate every flagged site as a JSON-serializable finding, so CI
+        // can diff the set of panic-reachable sites across commits.
+        let report_path = report::output_path(cx, "panic_reachability");
+        let mut findings = Vec::new();
+
+        for (accessor, accessees) in forward.iter() {
+            // Don't report on non-local items
+            if !accessor.def_id().is_local() {
+                continue;
+            }
+
+            // Fast path
+            if !panicking.contains(accessor) {
+                continue;
+            }
+
+            for item in accessees {
+                let accessee = item.node;
+
+                if (!accessee.def_id().is_local() || virtual_sinks.contains(&accessee))
+                    && panicking.contains(&accessee)
+                {
+                    cx.struct_span_lint(&PANIC, item.span, |diag| {
+                        let is_generic = accessor.substs.non_erasable_generics().next().is_some();
+                        let generic_note = if is_generic {
+                            format!(
+                                " when the caller is monomorphized as `{}`",
+                                cx.tcx
+                                    .def_path_str_with_substs(accessor.def_id(), accessor.substs)
+                            )
+                        } else {
+                            String::new()
+                        };
+
+                        let accessee_path = cx
+                            .tcx
+                            .def_path_str_with_substs(accessee.def_id(), accessee.substs);
+
+                        let mut diag = diag.build(&format!(
+                            "`{}` may panic{}",
+                            accessee_path, generic_note
+                        ));
+
+                        // For generic functions try to display a stacktrace until a non-generic one.
+                        let mut visited = FxHashSet::default();
+                        visited.insert(*accessor);
+                        visited.insert(accessee);
+                        let caller_chain = mono_graph::note_generic_callers(
+                            cx, &mut diag, &backward, &mut visited, *accessor,
+                        );
+
+                        // Generate some help messages for why the function is determined to be panic-reachable.
+                        let callee_chain = mono_graph::note_property_chain(
+                            cx,
+                            &mut diag,
+                            &forward,
+                            &panicking,
+                            &mut visited,
+                            accessee,
+                            &accessee_path,
+                            "may call panic handler",
+                            "is determined to be panic-reachable",
+                        );
+
+                        if report_path.is_some() {
+                            let mut chain: Vec<_> =
+                                caller_chain.iter().map(|hop| report::chain_hop(cx, hop)).collect();
+                            chain.extend(callee_chain.iter().map(|hop| report::chain_hop(cx, hop)));
+                            findings.push(report::Finding {
+                                accessor: cx
+                                    .tcx
+                                    .def_path_str_with_substs(accessor.def_id(), accessor.substs),
+                                accessor_span: report::loc(cx, item.span),
+                                accessee: accessee_path.clone(),
+                                chain,
+                            });
+                        }
+
+                        diag.emit();
+                    });
+                }
+            }
+        }
+
+        if let Some(path) = &report_path {
+            report::write(path, &findings);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! A self-contained mono-item reachability walk.
+//!
+//! This replaces a dependency on `rustc_mir::monomorphize::collector` and
+//! `MonoItemCollectionMode`, a deep internal API that has broken across compiler versions in
+//! the past. Instead we walk each root `Instance`'s optimized MIR terminators ourselves,
+//! resolving callees via `Instance::resolve` and monomorphizing with the caller's substs. This
+//! is a smaller, auditable surface that produces the same shape of forward/backward graph the
+//! lints in this crate consume, including identity-substituted roots for uncalled local generic
+//! items so eager mode's dead-code coverage isn't lost (see `walk` below) — though unlike the
+//! real collector, calls gated behind a root's own unresolved generic params are invisible to us
+//! rather than expanded, since we never instantiate those params with a concrete caller.
+use rustc_data_structures::fx::{FxHashMap, FxHashSet};
+use rustc_hir::LangItem;
+use rustc_lint::LateContext;
+use rustc_middle::mir::{AssertKind, CastKind, Operand, Rvalue, StatementKind, TerminatorKind};
+use rustc_middle::ty::adjustment::PointerCast;
+use rustc_middle::ty::{self, Instance, InstanceDef, ParamEnv, TyCtxt};
+use rustc_span::source_map::Spanned;
+use rustc_span::Span;
+
+pub struct Reachability<'tcx> {
+    pub forward: FxHashMap<Instance<'tcx>, Vec<Spanned<Instance<'tcx>>>>,
+    pub backward: FxHashMap<Instance<'tcx>, Vec<Spanned<Instance<'tcx>>>>,
+}
+
+/// Walk every local item's MIR and follow calls, drop glue, and function-item operands to build
+/// up the reachable call graph.
+///
+/// Roots are seeded with every local body owner, not just non-generic ones: `Instance::mono`
+/// can't represent a still-generic item (it asserts there's nothing left to substitute), but
+/// `MonoItemCollectionMode::Eager` also synthesizes an identity-substituted mono item for local
+/// generic functions that are never called from a non-generic context, specifically so dead
+/// generic code still gets linted. We reproduce that by seeding an identity-substituted
+/// `Instance` for every body owner, generic or not (identity substs degrade to the same thing
+/// `Instance::mono` would produce when there's nothing generic to substitute). Calls inside such
+/// a body that depend on the function's own unresolved type parameters simply fail to resolve in
+/// `resolve_call` and are skipped, the same conservative gap `Instance::resolve` already has for
+/// unresolvable `dyn Trait` dispatch.
+pub fn walk<'tcx>(cx: &LateContext<'tcx>) -> Reachability<'tcx> {
+    let tcx = cx.tcx;
+    let param_env = ParamEnv::reveal_all();
+
+    let roots: Vec<_> = tcx
+        .hir()
+        .body_owners()
+        .map(|local_def_id| local_def_id.to_def_id())
+        .map(|def_id| Instance::new(def_id, ty::InternalSubsts::identity_for_item(tcx, def_id)))
+        .collect();
+
+    let mut forward = FxHashMap::default();
+    let mut backward = FxHashMap::<_, Vec<_>>::default();
+    let mut visited = FxHashSet::default();
+    let mut queue = roots;
+
+    while let Some(instance) = queue.pop() {
+        if !visited.insert(instance) {
+            continue;
+        }
+
+        // Stop at foreign/non-local leaves, and at anything we don't have a MIR body for
+        // (intrinsics, `extern` items, etc). Drop glue is a synthetic shim (its `DefId` is
+        // `core::ptr::drop_in_place`, with the dropped type carried alongside), but `tcx.instance_mir` builds its
+        // body on demand just like any other shim, and that body already contains the `Drop::drop`
+        // call plus further `Drop` terminators for each field — walking it the same way as a
+        // regular item's MIR is exactly what recurses into field drop glue.
+        match instance.def {
+            InstanceDef::Item(def_id) => {
+                if !tcx.is_mir_available(def_id) {
+                    continue;
+                }
+            }
+            InstanceDef::DropGlue(..) => (),
+            // Virtual calls, foreign-crate shims, intrinsics, etc: opaque leaves we can't walk
+            // into any further.
+            _ => continue,
+        }
+
+        let body = tcx.instance_mir(instance.def);
+        let fwd_list = forward.entry(instance).or_insert_with(Vec::new);
+        let mut link = |callee: Instance<'tcx>, span: Span| {
+            fwd_list.push(Spanned { node: callee, span });
+            backward.entry(callee).or_default().push(Spanned {
+                node: instance,
+                span,
+            });
+            queue.push(callee);
+        };
+
+        for block in body.basic_blocks() {
+            let terminator = block.terminator();
+            let span = terminator.source_info.span;
+
+            match &terminator.kind {
+                TerminatorKind::Call { func, .. } => {
+                    if let Some(callee) = resolve_call(tcx, param_env, instance, func, false) {
+                        link(callee, span);
+                    }
+                }
+                TerminatorKind::Drop { place, .. } | TerminatorKind::DropAndReplace { place, .. } => {
+                    let ty = place.ty(&body.local_decls, tcx).ty;
+                    let ty = instance.subst_mir_and_normalize_erasing_regions(tcx, param_env, ty);
+                    if ty.needs_drop(tcx, param_env) {
+                        link(Instance::resolve_drop_in_place(tcx, ty), span);
+                    }
+                }
+                // Bounds checks, arithmetic-overflow checks, and div/rem-by-zero checks lower
+                // to an `Assert` terminator in MIR; the `panic_bounds_check`/`panic` lang-item
+                // call it fails into is only synthesized later, during codegen. Resolve and link
+                // it here the same way the real mono-item collector does, or this is invisible
+                // to both lints for what is ordinarily their single biggest source of panics.
+                TerminatorKind::Assert { msg, .. } => {
+                    let lang_item = match &**msg {
+                        AssertKind::BoundsCheck { .. } => LangItem::PanicBoundsCheck,
+                        _ => LangItem::Panic,
+                    };
+                    let def_id = tcx.require_lang_item(lang_item, Some(span));
+                    link(Instance::mono(tcx, def_id), span);
+                }
+                _ => (),
+            }
+
+            // Function items/closures referenced as bare values (e.g. a fn pointer or a
+            // function reference passed to a higher-order call) don't show up as `Call`
+            // terminators at their use site, so pick them up from the statements too. A function
+            // item coerced to a function pointer (`let f: fn() = some_fn;`, storing one in a
+            // `static`/struct field, etc) doesn't show up as a plain `Use` either — it lowers to
+            // a `Cast` with `PointerCast::ReifyFnPointer`/`ClosureFnPointer`, so match that too.
+            for stmt in &block.statements {
+                let operand = match &stmt.kind {
+                    StatementKind::Assign(box (_, Rvalue::Use(operand))) => Some(operand),
+                    StatementKind::Assign(box (
+                        _,
+                        Rvalue::Cast(
+                            CastKind::Pointer(
+                                PointerCast::ReifyFnPointer | PointerCast::ClosureFnPointer(_),
+                            ),
+                            operand,
+                            _,
+                        ),
+                    )) => Some(operand),
+                    _ => None,
+                };
+                if let Some(operand) = operand {
+                    if let Some(callee) = resolve_call(tcx, param_env, instance, operand, true) {
+                        link(callee, stmt.source_info.span);
+                    }
+                }
+            }
+        }
+    }
+
+    Reachability { forward, backward }
+}
+
+/// Resolve a function-item operand to the `Instance` it refers to. Direct calls go through
+/// `Instance::resolve`; function items taken as values (`for_fn_ptr`) go through
+/// `Instance::resolve_for_fn_ptr`, which yields a `ReifyShim` for `#[track_caller]` callees.
+fn resolve_call<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    param_env: ParamEnv<'tcx>,
+    caller: Instance<'tcx>,
+    operand: &Operand<'tcx>,
+    for_fn_ptr: bool,
+) -> Option<Instance<'tcx>> {
+    let fn_ty = operand.constant()?.literal.ty();
+    let (def_id, substs) = match fn_ty.kind() {
+        ty::FnDef(def_id, substs) => (*def_id, *substs),
+        _ => return None,
+    };
+    let substs = caller.subst_mir_and_normalize_erasing_regions(tcx, param_env, substs);
+    if for_fn_ptr {
+        Instance::resolve_for_fn_ptr(tcx, param_env, def_id, substs)
+    } else {
+        Instance::resolve(tcx, param_env, def_id, substs).ok().flatten()
+    }
+}
@@ -0,0 +1,81 @@
+//! Optional machine-readable JSON report of the reachability results, for CI regression gating.
+//!
+//! Enable it with the `KLINT_REACHABILITY_REPORT=<path>` environment variable, or per-crate
+//! with `#![klint::reachability_report = "<path>"]`. Each lint that opts in appends a
+//! `.<lint-name>.json` suffix to the given path, so `infallible_allocation` and
+//! `panic_reachability` don't clobber each other's reports. A CI job can then diff the set of
+//! flagged sites across commits instead of scraping human-oriented compiler diagnostics.
+use std::fs;
+
+use rustc_lint::LateContext;
+use rustc_middle::ty::Instance;
+use rustc_span::source_map::Spanned;
+use rustc_span::Span;
+use serde::Serialize;
+
+use crate::attributes;
+
+#[derive(Serialize)]
+pub struct Finding {
+    /// The local item whose monomorphization can reach `accessee`.
+    pub accessor: String,
+    pub accessor_span: SourceLoc,
+    /// The offending accessee, with its monomorphized generic instantiation (if any).
+    pub accessee: String,
+    /// The span-noted hops of the diagnostic: first the callers walked outward from `accessor`
+    /// while it is still generic (nearest caller first), then the callees walked from
+    /// `accessee` down to the leaf that gives it the flagged property.
+    pub chain: Vec<ChainHop>,
+}
+
+#[derive(Serialize)]
+pub struct ChainHop {
+    pub path: String,
+    pub span: SourceLoc,
+}
+
+#[derive(Serialize)]
+pub struct SourceLoc {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Is reporting enabled for this crate, and if so, what's the output path for `lint_name`?
+pub fn output_path(cx: &LateContext<'_>, lint_name: &str) -> Option<String> {
+    let base = std::env::var("KLINT_REACHABILITY_REPORT")
+        .ok()
+        .or_else(|| attributes::crate_str_attr(cx, "reachability_report"))?;
+    Some(format!("{}.{}.json", base, lint_name))
+}
+
+pub fn loc(cx: &LateContext<'_>, span: Span) -> SourceLoc {
+    let loc = cx.sess().source_map().lookup_char_pos(span.lo());
+    SourceLoc {
+        file: loc.file.name.prefer_remapped().to_string(),
+        line: loc.line,
+        column: loc.col.0 + 1,
+    }
+}
+
+pub fn chain_hop<'tcx>(cx: &LateContext<'tcx>, hop: &Spanned<Instance<'tcx>>) -> ChainHop {
+    ChainHop {
+        path: cx
+            .tcx
+            .def_path_str_with_substs(hop.node.def_id(), hop.node.substs),
+        span: loc(cx, hop.span),
+    }
+}
+
+pub fn write(path: &str, findings: &[Finding]) {
+    let json = match serde_json::to_string_pretty(findings) {
+        Ok(json) => json,
+        Err(err) => {
+            eprintln!("klint: failed to serialize reachability report: {}", err);
+            return;
+        }
+    };
+    if let Err(err) = fs::write(path, json) {
+        eprintln!("klint: failed to write reachability report to {}: {}", path, err);
+    }
+}